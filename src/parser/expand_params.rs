@@ -0,0 +1,267 @@
+//! Parameter expansion for `${VAR}`, `${VAR:-default}`, `${VAR:=default}`, `${VAR:+alt}`, and
+//! `${VAR:?message}`, plus the bare `$VAR` form.
+//!
+//! This operates on the argument strings that `parser::pipelines::collect` already produced, so
+//! it re-derives the same backslash/quote bookkeeping rather than sharing it, matching how the
+//! pipeline and argument scanners each keep their own copy of that state.
+
+const BACKSLASH:    u8 = 1;
+const SINGLE_QUOTE: u8 = 2;
+const DOUBLE_QUOTE: u8 = 4;
+
+/// The modifier that follows a variable name inside `${...}`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExpansionOp {
+    /// `${VAR}` / `$VAR` -- substitute the variable's value, or an empty string if unset.
+    None,
+    /// `${VAR:-word}` -- substitute `word` when `VAR` is unset or empty.
+    UseDefault,
+    /// `${VAR:=word}` -- like `UseDefault`. Actually assigning `word` back to `VAR` is left to
+    /// the caller, since this module only resolves expansions and has no access to the
+    /// environment it would assign into.
+    AssignDefault,
+    /// `${VAR:+word}` -- substitute `word` only when `VAR` is set and non-empty.
+    UseAlternate,
+    /// `${VAR:?word}` -- when `VAR` is unset or empty, `word` is the error message to report.
+    ErrorIfUnset,
+}
+
+/// A single `${VAR[:op]word}` or bare `$VAR` reference found inside an argument.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParamExpansion {
+    pub name: String,
+    pub op:   ExpansionOp,
+    pub word: String,
+    span:     (usize, usize),
+}
+
+fn is_identifier_byte(byte: u8) -> bool {
+    byte == b'_' || (byte as char).is_alphanumeric()
+}
+
+/// Splits `${NAME:op WORD}`'s inner text into its name, operator, and word, honoring nested
+/// `${...}` inside `WORD` so the `:` that introduces the operator isn't confused with one deeper
+/// in a nested expansion.
+fn parse_inner(inner: &str, span: (usize, usize)) -> ParamExpansion {
+    let bytes = inner.as_bytes();
+    let mut depth = 0;
+
+    for index in 0..bytes.len() {
+        match bytes[index] {
+            b'{' => depth += 1,
+            b'}' => depth -= 1,
+            b':' if depth == 0 && index + 1 < bytes.len() => {
+                let op = match bytes[index + 1] {
+                    b'-' => Some(ExpansionOp::UseDefault),
+                    b'=' => Some(ExpansionOp::AssignDefault),
+                    b'+' => Some(ExpansionOp::UseAlternate),
+                    b'?' => Some(ExpansionOp::ErrorIfUnset),
+                    _    => None,
+                };
+                if let Some(op) = op {
+                    return ParamExpansion {
+                        name: inner[..index].to_owned(),
+                        op:   op,
+                        word: inner[index + 2..].to_owned(),
+                        span: span,
+                    };
+                }
+            },
+            _ => (),
+        }
+    }
+
+    ParamExpansion { name: inner.to_owned(), op: ExpansionOp::None, word: String::new(), span: span }
+}
+
+/// Parses the `${...}` or bare `$VAR` expansion starting at `text[0] == '$'`, returning it along
+/// with the number of bytes it consumes. Returns `None` for a lone `$` or an unterminated `${`,
+/// both of which are left as literal text.
+fn parse_expansion(text: &str, offset: usize) -> Option<(ParamExpansion, usize)> {
+    let bytes = text.as_bytes();
+    if bytes.len() < 2 { return None }
+
+    if bytes[1] == b'{' {
+        let mut depth = 1;
+        let mut index = 2;
+        while index < bytes.len() {
+            match bytes[index] {
+                b'{' => depth += 1,
+                b'}' => {
+                    depth -= 1;
+                    if depth == 0 { break }
+                },
+                _ => (),
+            }
+            index += 1;
+        }
+
+        if depth != 0 { return None }
+
+        let consumed = index + 1;
+        Some((parse_inner(&text[2..index], (offset, offset + consumed)), consumed))
+    } else {
+        let mut index = 1;
+        while index < bytes.len() && is_identifier_byte(bytes[index]) { index += 1; }
+        if index == 1 { return None }
+
+        let expansion = ParamExpansion {
+            name: text[1..index].to_owned(),
+            op:   ExpansionOp::None,
+            word: String::new(),
+            span: (offset, offset + index),
+        };
+        Some((expansion, index))
+    }
+}
+
+/// Scans `argument` for every `${...}`/`$VAR` reference outside single quotes.
+pub fn scan(argument: &str) -> Vec<ParamExpansion> {
+    let bytes = argument.as_bytes();
+    let mut expansions = Vec::new();
+    let mut flags = 0u8;
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            _ if flags & BACKSLASH != 0                => flags ^= BACKSLASH,
+            b'\\'                                       => flags ^= BACKSLASH,
+            b'\'' if flags & DOUBLE_QUOTE == 0          => flags ^= SINGLE_QUOTE,
+            b'"'  if flags & SINGLE_QUOTE == 0          => flags ^= DOUBLE_QUOTE,
+            b'$'  if flags & SINGLE_QUOTE == 0 => {
+                if let Some((expansion, consumed)) = parse_expansion(&argument[index..], index) {
+                    expansions.push(expansion);
+                    index += consumed;
+                    continue
+                }
+            },
+            _ => (),
+        }
+        index += 1;
+    }
+
+    expansions
+}
+
+/// Expands every parameter reference in `argument`, resolving variables with `lookup`.
+///
+/// Returns `Err` with the `:?` message when an `ErrorIfUnset` expansion's variable is unset or
+/// empty, matching the POSIX shell behavior of aborting rather than substituting anything.
+pub fn expand<F: Fn(&str) -> Option<String> + Copy>(argument: &str, lookup: F) -> Result<String, String> {
+    let expansions = scan(argument);
+    if expansions.is_empty() { return Ok(argument.to_owned()) }
+
+    let mut output = String::with_capacity(argument.len());
+    let mut cursor = 0;
+
+    for expansion in expansions {
+        output.push_str(&argument[cursor..expansion.span.0]);
+        output.push_str(&resolve(&expansion, lookup)?);
+        cursor = expansion.span.1;
+    }
+    output.push_str(&argument[cursor..]);
+    Ok(output)
+}
+
+fn resolve<F: Fn(&str) -> Option<String> + Copy>(expansion: &ParamExpansion, lookup: F) -> Result<String, String> {
+    let value = lookup(&expansion.name);
+    let is_set_and_non_empty = value.as_ref().map_or(false, |value| !value.is_empty());
+
+    match expansion.op {
+        ExpansionOp::None => Ok(value.unwrap_or_default()),
+        ExpansionOp::UseDefault | ExpansionOp::AssignDefault => {
+            if is_set_and_non_empty { Ok(value.unwrap()) } else { expand(&expansion.word, lookup) }
+        },
+        ExpansionOp::UseAlternate => {
+            if is_set_and_non_empty { expand(&expansion.word, lookup) } else { Ok(String::new()) }
+        },
+        ExpansionOp::ErrorIfUnset => {
+            if is_set_and_non_empty {
+                Ok(value.unwrap())
+            } else if expansion.word.is_empty() {
+                Err(format!("{}: parameter not set", expansion.name))
+            } else {
+                Err(expand(&expansion.word, lookup)?)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|&(key, value)| (key.to_owned(), value.to_owned())).collect()
+    }
+
+    #[test]
+    fn plain_braced_variable() {
+        let vars = env(&[("EDITOR", "vim")]);
+        assert_eq!(Ok("vim".to_owned()), expand("${EDITOR}", |name| vars.get(name).cloned()));
+    }
+
+    #[test]
+    fn bare_variable() {
+        let vars = env(&[("EDITOR", "vim")]);
+        assert_eq!(Ok("vim!".to_owned()), expand("$EDITOR!", |name| vars.get(name).cloned()));
+    }
+
+    #[test]
+    fn use_default_when_unset() {
+        let vars = env(&[]);
+        assert_eq!(Ok("vi".to_owned()), expand("${EDITOR:-vi}", |name| vars.get(name).cloned()));
+    }
+
+    #[test]
+    fn use_default_when_set() {
+        let vars = env(&[("EDITOR", "vim")]);
+        assert_eq!(Ok("vim".to_owned()), expand("${EDITOR:-vi}", |name| vars.get(name).cloned()));
+    }
+
+    #[test]
+    fn use_default_when_empty() {
+        let vars = env(&[("EDITOR", "")]);
+        assert_eq!(Ok("vi".to_owned()), expand("${EDITOR:-vi}", |name| vars.get(name).cloned()));
+    }
+
+    #[test]
+    fn use_alternate_when_set() {
+        let vars = env(&[("DEBUG", "1")]);
+        assert_eq!(Ok("-v".to_owned()), expand("${DEBUG:+-v}", |name| vars.get(name).cloned()));
+    }
+
+    #[test]
+    fn use_alternate_when_unset() {
+        let vars = env(&[]);
+        assert_eq!(Ok("".to_owned()), expand("${DEBUG:+-v}", |name| vars.get(name).cloned()));
+    }
+
+    #[test]
+    fn single_quoted_text_is_not_expanded() {
+        let vars = env(&[("x", "hi")]);
+        assert_eq!(Ok("'${x}'".to_owned()), expand("'${x}'", |name| vars.get(name).cloned()));
+    }
+
+    #[test]
+    fn multiple_expansions_in_one_argument() {
+        let vars = env(&[("A", "1"), ("B", "2")]);
+        assert_eq!(Ok("1-2".to_owned()), expand("${A}-${B}", |name| vars.get(name).cloned()));
+    }
+
+    #[test]
+    fn error_if_unset_reports_message_instead_of_substituting() {
+        let vars = env(&[]);
+        assert_eq!(
+            Err("EDITOR not set".to_owned()),
+            expand("${EDITOR:?EDITOR not set}", |name| vars.get(name).cloned())
+        );
+    }
+
+    #[test]
+    fn error_if_unset_passes_through_when_set() {
+        let vars = env(&[("EDITOR", "vim")]);
+        assert_eq!(Ok("vim".to_owned()), expand("${EDITOR:?EDITOR not set}", |name| vars.get(name).cloned()));
+    }
+}
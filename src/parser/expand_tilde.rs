@@ -0,0 +1,128 @@
+//! Tilde expansion for `~` and `~user`, the way `collect` leaves arguments for later expansion
+//! passes to pick up (see `expand_params`).
+//!
+//! A leading `~` only expands at the start of a word or immediately after `=`/`:`, so that
+//! `PATH=~/bin` and `--dir=~user/x` work without turning every mid-word `~` into a lookup.
+
+const BACKSLASH:    u8 = 1;
+const SINGLE_QUOTE: u8 = 2;
+const DOUBLE_QUOTE: u8 = 4;
+
+/// A single `~` or `~user` reference found at the start of a word, or just after `=`/`:`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TildeExpansion {
+    /// The text between `~` and the end of the run, e.g. `""` for `~` or `"root"` for `~root`.
+    pub user: String,
+    span:     (usize, usize),
+}
+
+fn is_name_byte(byte: u8) -> bool {
+    byte != b'/' && !(byte as char).is_whitespace()
+}
+
+/// Scans `argument` for every unquoted tilde prefix eligible for expansion.
+pub fn scan(argument: &str) -> Vec<TildeExpansion> {
+    let bytes = argument.as_bytes();
+    let mut expansions = Vec::new();
+    let mut flags = 0u8;
+    let mut index = 0;
+    let mut boundary = true;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            _ if flags & BACKSLASH != 0                 => { flags ^= BACKSLASH; boundary = false; },
+            b'\\'                                        => { flags ^= BACKSLASH; boundary = false; },
+            b'\'' if flags & DOUBLE_QUOTE == 0           => { flags ^= SINGLE_QUOTE; boundary = false; },
+            b'"'  if flags & SINGLE_QUOTE == 0           => { flags ^= DOUBLE_QUOTE; boundary = false; },
+            b'~'  if boundary && flags & (SINGLE_QUOTE | DOUBLE_QUOTE) == 0 => {
+                let start = index;
+                let mut end = index + 1;
+                while end < bytes.len() && is_name_byte(bytes[end]) { end += 1; }
+
+                expansions.push(TildeExpansion { user: argument[start + 1..end].to_owned(), span: (start, end) });
+                index = end;
+                boundary = false;
+                continue;
+            },
+            b'=' | b':' if flags & (SINGLE_QUOTE | DOUBLE_QUOTE) == 0 => boundary = true,
+            _                                             => boundary = false,
+        }
+        index += 1;
+    }
+
+    expansions
+}
+
+/// Expands every eligible `~`/`~user` prefix in `argument`, resolving home directories with
+/// `home_of`. `home_of("")` is asked for the current user's home; a lookup that returns `None`
+/// leaves the original text in place rather than failing.
+pub fn expand<F: Fn(&str) -> Option<String>>(argument: &str, home_of: F) -> String {
+    let expansions = scan(argument);
+    if expansions.is_empty() { return argument.to_owned() }
+
+    let mut output = String::with_capacity(argument.len());
+    let mut cursor = 0;
+
+    for expansion in expansions {
+        output.push_str(&argument[cursor..expansion.span.0]);
+        match home_of(&expansion.user) {
+            Some(home) => output.push_str(&home),
+            None       => output.push_str(&argument[expansion.span.0..expansion.span.1]),
+        }
+        cursor = expansion.span.1;
+    }
+    output.push_str(&argument[cursor..]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn homes(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|&(key, value)| (key.to_owned(), value.to_owned())).collect()
+    }
+
+    #[test]
+    fn bare_tilde() {
+        let homes = homes(&[("", "/home/user")]);
+        assert_eq!("/home/user", expand("~", |user| homes.get(user).cloned()));
+    }
+
+    #[test]
+    fn tilde_with_path() {
+        let homes = homes(&[("", "/home/user")]);
+        assert_eq!("/home/user/bin", expand("~/bin", |user| homes.get(user).cloned()));
+    }
+
+    #[test]
+    fn tilde_user() {
+        let homes = homes(&[("root", "/root")]);
+        assert_eq!("/root", expand("~root", |user| homes.get(user).cloned()));
+    }
+
+    #[test]
+    fn tilde_after_assignment() {
+        let homes = homes(&[("", "/home/user")]);
+        assert_eq!("PATH=/home/user/bin", expand("PATH=~/bin", |user| homes.get(user).cloned()));
+    }
+
+    #[test]
+    fn unresolved_user_is_left_unchanged() {
+        let homes = homes(&[]);
+        assert_eq!("~nobody", expand("~nobody", |user| homes.get(user).cloned()));
+    }
+
+    #[test]
+    fn mid_word_tilde_is_literal() {
+        let homes = homes(&[("", "/home/user")]);
+        assert_eq!("a~b", expand("a~b", |user| homes.get(user).cloned()));
+    }
+
+    #[test]
+    fn quoted_tilde_is_not_expanded() {
+        let homes = homes(&[("", "/home/user")]);
+        assert_eq!("\"~\"", expand("\"~\"", |user| homes.get(user).cloned()));
+    }
+}
@@ -1,4 +1,7 @@
-use parser::peg::{Job, Pipeline, Redirection};
+use std::iter::Peekable;
+
+use flow_control::Statement;
+use parser::peg::{Connector, Job, Pipeline, Redirection, RedirectTarget};
 
 const BACKSLASH:    u8 = 1;
 const SINGLE_QUOTE: u8 = 2;
@@ -13,23 +16,29 @@ const PROCESS_VAL:  u8 = 255 ^ (BACKSLASH + WHITESPACE + 32);
 // Determines if the character is not quoted and isn't process matched. `flags & IS_VALID` returns 0 if true
 const IS_VALID: u8 = 255 ^ (BACKSLASH + WHITESPACE);
 
-/// An iterator that splits a given command into pipelines -- individual command statements delimited by ';'.
+/// An iterator that splits a given command into pipelines -- individual command statements
+/// delimited by `;` or a newline, honoring quoting and command/arithmetic substitution so that a
+/// `;` inside either is left alone.
 struct PipelineIterator<'a> {
     match_str:       &'a str,
     flags:           u8,
     index_start:     usize,
     index_end:       usize,
     white_pos:       usize,
+    // Counts nested `(` inside a `$(...)`/`$((...))` span, since unlike every other piece of
+    // state here a single bit can't tell `$((1+2))` apart from `$(seq 1)(`.
+    process_depth:   u32,
 }
 
 impl<'a> PipelineIterator<'a> {
     fn new(match_str: &'a str) -> PipelineIterator<'a> {
         PipelineIterator {
-            match_str:   match_str,
-            flags:       0,
-            index_start: 0,
-            index_end:   0,
-            white_pos:   0,
+            match_str:     match_str,
+            flags:         0,
+            index_start:   0,
+            index_end:     0,
+            white_pos:     0,
+            process_depth: 0,
         }
     }
 }
@@ -44,8 +53,15 @@ impl<'a> Iterator for PipelineIterator<'a> {
                 b'\'' if self.flags & (PROCESS_TWO + DOUBLE_QUOTE) == 0  => self.flags ^= SINGLE_QUOTE,
                 b'"'  if self.flags & (PROCESS_TWO + SINGLE_QUOTE) == 0  => self.flags ^= DOUBLE_QUOTE,
                 b'$'  if self.flags & PROCESS_VAL == 0                   => self.flags |= PROCESS_ONE,
-                b'('  if self.flags & PROCESS_VAL == PROCESS_ONE         => self.flags ^= PROCESS_ONE + PROCESS_TWO,
-                b')'  if self.flags & PROCESS_VAL == PROCESS_TWO         => self.flags &= 255 ^ PROCESS_TWO,
+                b'('  if self.flags & PROCESS_VAL == PROCESS_ONE         => {
+                    self.flags ^= PROCESS_ONE + PROCESS_TWO;
+                    self.process_depth = 1;
+                },
+                b'('  if self.flags & PROCESS_VAL == PROCESS_TWO         => self.process_depth += 1,
+                b')'  if self.flags & PROCESS_VAL == PROCESS_TWO         => {
+                    self.process_depth -= 1;
+                    if self.process_depth == 0 { self.flags &= 255 ^ PROCESS_TWO; }
+                },
                 b' ' | b'\t' if self.flags & IS_VALID == 0 => {
                     if self.index_start == self.index_end { self.index_start += 1; }
                     self.flags |= WHITESPACE;
@@ -53,6 +69,17 @@ impl<'a> Iterator for PipelineIterator<'a> {
                     self.index_end += 1;
                     continue
                 },
+                b';' | b'\n' if self.flags & IS_VALID == 0 => {
+                    let command = &self.match_str[self.index_start..self.index_end];
+                    self.index_end += 1;
+                    self.index_start = self.index_end;
+                    self.flags &= 255 ^ WHITESPACE;
+                    self.white_pos = 0;
+                    if command.chars().any(|x| x != ' ' && x != '\n' && x != '\r' && x != '\t') {
+                        return Some(command)
+                    }
+                    continue
+                },
                 _ if (self.flags >> 6 != 2) => self.flags &= 255 ^ (PROCESS_ONE + PROCESS_TWO),
                 _ => (),
             }
@@ -76,33 +103,85 @@ impl<'a> Iterator for PipelineIterator<'a> {
 }
 
 #[derive(PartialEq)]
-enum RedirMode { False, Stdin, Stdout, StdoutAppend }
+enum RedirMode { False, Stdin, Stdout, StdoutAppend, Stderr, StderrAppend, Dup }
+
+/// Looks back over the run of bytes immediately preceding a redirection operator and, if that
+/// run is a non-empty string of ASCII digits (e.g. the `2` in `2>`), parses it as an explicit
+/// file-descriptor number.
+fn take_fd_prefix(args: &str, start: usize, end: usize) -> Option<i32> {
+    if end > start && args.as_bytes()[start..end].iter().all(|byte| byte.is_ascii_digit()) {
+        args[start..end].parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Reads the filename that follows a redirection operator, skipping the leading run of
+/// whitespace/pipe between the operator and the target, then stopping at the first unescaped
+/// whitespace or pipe after that, honoring the same backslash-escaping rules as the rest of the
+/// argument scanner.
+fn read_redirection_target<I: Iterator<Item = u8>>(
+    iter:  &mut Peekable<I>,
+    index: &mut usize,
+    flags: &mut u8,
+) -> Vec<u8> {
+    let mut name = Vec::new();
+    while let Some(&character) = iter.peek() {
+        if *flags & BACKSLASH != 0 {
+            name.push(character);
+            *flags ^= BACKSLASH;
+        } else if character == b'\\' {
+            *flags ^= BACKSLASH;
+        } else if (character == b' ' || character == b'\t' || character == b'|') && name.is_empty() {
+            // Leading separator between the operator and the target; not part of the name.
+        } else if character == b' ' || character == b'\t' || character == b'|' {
+            break
+        } else {
+            name.push(character);
+        }
+        iter.next();
+        *index += 1;
+    }
+    name
+}
 
 /// Parses each individual pipeline, separating arguments, pipes, background tasks, and redirections.
-pub fn collect(pipelines: &mut Vec<Pipeline>, possible_error: &mut Option<&str>, command: &str) {
+///
+/// Pipelines within the same statement are linked by a [`Connector`], so that `a && b` only
+/// executes `b` when `a` succeeds and `a || b` only executes `b` when `a` fails.
+pub fn collect(pipelines: &mut Vec<(Pipeline, Connector)>, possible_error: &mut Option<&str>, command: &str) {
     for args in PipelineIterator::new(command) {
         let mut jobs: Vec<Job> = Vec::new();
-        let mut args_iter = args.bytes();
+        let mut args_iter = args.bytes().peekable();
         let (mut index, mut arg_start) = (0, 0);
         let mut flags = 0u8; // (backslash, single_quote, double_quote, x, x, x, process_one, process_two)
+        // Counts nested `(` inside a `$(...)`/`$((...))` span; see `PipelineIterator::process_depth`.
+        let mut process_depth = 0u32;
 
         let mut arguments: Vec<String> = Vec::new();
 
-        let (mut in_file, mut out_file) = (None, None);
+        let mut redirections: Vec<Redirection> = Vec::new();
         let mut mode = RedirMode::False;
+        // How this pipeline relates to the one before it within the same statement.
+        let mut connector = Connector::Always;
+        // The source fd captured by a preceding digit run (e.g. the `2` in `2>`), defaulting to
+        // stdout (1) for `>` and stdin (0) for `<`.
+        let mut redir_from = 1i32;
+        // Set when `&>` was seen, so the resulting redirection is duplicated onto stderr as well.
+        let mut redir_both = false;
 
         macro_rules! redir_check {
-            ($file:ident, $name:ident, $is_append:expr) => {{
-                if $file.is_none() {
-                    if $name.is_empty() {
-                        *possible_error = Some("missing standard output file argument after '>'");
-                    } else {
-                        $file = Some(Redirection {
-                            file: unsafe { String::from_utf8_unchecked($name) },
-                            append: $is_append
-                        });
+            ($name:ident, $from:expr, $is_append:expr) => {{
+                if $name.is_empty() {
+                    *possible_error = Some("missing file argument after redirection operator");
+                } else {
+                    let target = RedirectTarget::File(unsafe { String::from_utf8_unchecked($name) });
+                    redirections.push(Redirection { from: $from, to: target.clone(), append: $is_append });
+                    if redir_both {
+                        redirections.push(Redirection { from: 2, to: target, append: $is_append });
                     }
                 }
+                redir_both = false;
             }}
         }
 
@@ -127,6 +206,22 @@ pub fn collect(pipelines: &mut Vec<Pipeline>, possible_error: &mut Option<&str>,
                 }}
             }
 
+            // Closes out the pipeline built so far, links it to the one before it via the
+            // in-progress `connector`, and arms `connector` for the pipeline that follows.
+            macro_rules! pipeline_found {
+                ($next_connector:expr) => {{
+                    args_iter.next();
+                    job_found!(false);
+                    index += 1;
+                    arg_start = index + 1;
+                    pipelines.push((Pipeline::new(jobs.clone(), redirections.clone()), connector));
+                    jobs.clear();
+                    redirections.clear();
+                    redir_both = false;
+                    connector = $next_connector;
+                }}
+            }
+
             match mode {
                 RedirMode::False => {
                     while let Some(character) = args_iter.next() {
@@ -134,8 +229,15 @@ pub fn collect(pipelines: &mut Vec<Pipeline>, possible_error: &mut Option<&str>,
                             _ if flags & BACKSLASH != 0                 => flags ^= BACKSLASH,
                             b'\\'                                       => flags ^= BACKSLASH,
                             b'$'  if flags & PROCESS_VAL == 0           => flags |= PROCESS_ONE,
-                            b'('  if flags & PROCESS_VAL == PROCESS_ONE => flags ^= PROCESS_ONE + PROCESS_TWO,
-                            b')'  if flags & PROCESS_VAL == PROCESS_TWO => flags &= 255 ^ PROCESS_TWO,
+                            b'('  if flags & PROCESS_VAL == PROCESS_ONE => {
+                                flags ^= PROCESS_ONE + PROCESS_TWO;
+                                process_depth = 1;
+                            },
+                            b'('  if flags & PROCESS_VAL == PROCESS_TWO => process_depth += 1,
+                            b')'  if flags & PROCESS_VAL == PROCESS_TWO => {
+                                process_depth -= 1;
+                                if process_depth == 0 { flags &= 255 ^ PROCESS_TWO; }
+                            },
                             b'\''                                       => flags ^= SINGLE_QUOTE,
                             b'"'                                        => flags ^= DOUBLE_QUOTE,
                             b' ' | b'\t' if (flags & IS_VALID == 0) => {
@@ -146,10 +248,39 @@ pub fn collect(pipelines: &mut Vec<Pipeline>, possible_error: &mut Option<&str>,
                                     arg_start += 1;
                                 }
                             },
+                            b'|' if (flags & (255 ^ BACKSLASH) == 0) && args_iter.peek() == Some(&b'|') => {
+                                pipeline_found!(Connector::OrIf)
+                            },
                             b'|' if (flags & (255 ^ BACKSLASH) == 0) => job_found!(false),
+                            b'&' if (flags & IS_VALID == 0) && args_iter.peek() == Some(&b'>') => {
+                                args_iter.next();
+                                index += 1;
+                                redir_from = 1;
+                                redir_both = true;
+                                redir_found!(RedirMode::Stdout)
+                            },
+                            b'&' if (flags & IS_VALID == 0) && args_iter.peek() == Some(&b'&') => {
+                                pipeline_found!(Connector::AndIf)
+                            },
                             b'&' if (flags & IS_VALID == 0) => job_found!(true),
-                            b'>' if (flags & IS_VALID == 0) => redir_found!(RedirMode::Stdout),
-                            b'<' if (flags & IS_VALID == 0) => redir_found!(RedirMode::Stdin),
+                            b'>' if (flags & IS_VALID == 0) => {
+                                redir_from = take_fd_prefix(args, arg_start, index).unwrap_or(1);
+                                redir_both = false;
+                                if args_iter.peek() == Some(&b'&') {
+                                    args_iter.next();
+                                    index += 1;
+                                    redir_found!(RedirMode::Dup)
+                                } else if redir_from == 2 {
+                                    redir_found!(RedirMode::Stderr)
+                                } else {
+                                    redir_found!(RedirMode::Stdout)
+                                }
+                            },
+                            b'<' if (flags & IS_VALID == 0) => {
+                                redir_from = take_fd_prefix(args, arg_start, index).unwrap_or(0);
+                                redir_both = false;
+                                redir_found!(RedirMode::Stdin)
+                            },
                             _   if (flags >> 6 != 2)        => flags &= 255 ^ (PROCESS_ONE + PROCESS_TWO),
                             _ => (),
                         }
@@ -157,123 +288,49 @@ pub fn collect(pipelines: &mut Vec<Pipeline>, possible_error: &mut Option<&str>,
                     }
                     break 'outer
                 },
-                RedirMode::Stdout | RedirMode::StdoutAppend => {
-                    match args_iter.next() {
-                        Some(character) => if character == b'>' { mode = RedirMode::StdoutAppend; },
-                        None => {
-                            *possible_error = Some("missing standard output file argument after '>'");
-                            break 'outer
-                        }
-                    }
-
-                    let mut stdout_file = Vec::new();
-                    let mut found_file = false;
-                    while let Some(character) = args_iter.next() {
-                        if found_file {
-                            if character == b'<' {
-                                if in_file.is_some() {
-                                    break 'outer
-                                } else {
-                                    mode = RedirMode::Stdin;
-                                    continue 'outer
-                                }
-                            }
-                        } else {
-                            match character {
-                                _ if flags & BACKSLASH != 0 => {
-                                    stdout_file.push(character);
-                                    flags ^= BACKSLASH;
-                                }
-                                b'\\' => flags ^= BACKSLASH,
-                                b' ' | b'\t' | b'|' if stdout_file.is_empty() => (),
-                                b' ' | b'\t' | b'|' => {
-                                    found_file = true;
-                                    out_file = Some(Redirection {
-                                        file: unsafe { String::from_utf8_unchecked(stdout_file.clone()) },
-                                        append: mode == RedirMode::StdoutAppend
-                                    });
-                                },
-                                b'<' if stdout_file.is_empty() => {
-                                    *possible_error = Some("missing standard output file argument after '>'");
-                                    break 'outer
-                                }
-                                b'<' => {
-                                    out_file = Some(Redirection {
-                                        file: unsafe { String::from_utf8_unchecked(stdout_file.clone()) },
-                                        append: mode == RedirMode::StdoutAppend
-                                    });
-
-                                    if in_file.is_some() {
-                                        break 'outer
-                                    } else {
-                                        mode = RedirMode::Stdin;
-                                        continue 'outer
-                                    }
-                                },
-                                _ => stdout_file.push(character),
-                            }
-                        }
+                RedirMode::Stdout | RedirMode::StdoutAppend | RedirMode::Stderr | RedirMode::StderrAppend => {
+                    if (mode == RedirMode::Stdout || mode == RedirMode::Stderr) && args_iter.peek() == Some(&b'>') {
+                        args_iter.next();
+                        index += 1;
+                        mode = if mode == RedirMode::Stderr { RedirMode::StderrAppend } else { RedirMode::StdoutAppend };
                     }
-
-                    redir_check!(out_file, stdout_file, mode == RedirMode::StdoutAppend);
-
-                    break 'outer
+                    let append = mode == RedirMode::StdoutAppend || mode == RedirMode::StderrAppend;
+                    let name = read_redirection_target(&mut args_iter, &mut index, &mut flags);
+                    redir_check!(name, redir_from, append);
+                    mode = RedirMode::False;
+                    arg_start = index;
+                    continue 'outer
                 },
                 RedirMode::Stdin => {
-                    let mut stdin_file = Vec::new();
-                    let mut found_file = false;
-
-                    while let Some(character) = args_iter.next() {
-                        if found_file {
-                            if character == b'>' {
-                                if out_file.is_some() {
-                                    break 'outer
-                                } else {
-                                    mode = RedirMode::Stdout;
-                                    continue 'outer
-                                }
-                            }
+                    let name = read_redirection_target(&mut args_iter, &mut index, &mut flags);
+                    redir_check!(name, redir_from, false);
+                    mode = RedirMode::False;
+                    arg_start = index;
+                    continue 'outer
+                },
+                RedirMode::Dup => {
+                    let mut target = Vec::new();
+                    while let Some(&character) = args_iter.peek() {
+                        if character.is_ascii_digit() {
+                            target.push(character);
+                            args_iter.next();
+                            index += 1;
                         } else {
-                            match character {
-                                _ if flags & BACKSLASH != 0 => {
-                                    stdin_file.push(character);
-                                    flags ^= BACKSLASH;
-                                }
-                                b'\\' => flags ^= BACKSLASH,
-                                b' ' | b'\t' | b'|' if stdin_file.is_empty() => (),
-                                b' ' | b'\t' | b'|' => {
-                                    found_file = true;
-                                    in_file = Some(Redirection {
-                                        file: unsafe { String::from_utf8_unchecked(stdin_file.clone()) },
-                                        append: false
-                                    });
-                                },
-                                b'>' if stdin_file.is_empty() => {
-                                    *possible_error = Some("missing standard input file argument after '<'");
-                                    break 'outer
-                                }
-                                b'>' => {
-                                    in_file = Some(Redirection {
-                                        file: unsafe { String::from_utf8_unchecked(stdin_file.clone()) },
-                                        append: false
-                                    });
-
-                                    if out_file.is_some() {
-                                        break 'outer
-                                    } else {
-                                        mode = RedirMode::Stdin;
-                                        continue 'outer
-                                    }
-                                },
-                                _ => stdin_file.push(character),
-                            }
+                            break
                         }
                     }
 
-                    redir_check!(in_file, stdin_file, false);
+                    if target.is_empty() {
+                        *possible_error = Some("missing file descriptor after '>&'");
+                    } else {
+                        let to_fd = unsafe { String::from_utf8_unchecked(target) }.parse().unwrap_or(redir_from);
+                        redirections.push(Redirection { from: redir_from, to: RedirectTarget::Fd(to_fd), append: false });
+                    }
 
-                    break 'outer
-                }
+                    mode = RedirMode::False;
+                    arg_start = index;
+                    continue 'outer
+                },
             }
         }
 
@@ -285,19 +342,157 @@ pub fn collect(pipelines: &mut Vec<Pipeline>, possible_error: &mut Option<&str>,
             jobs.push(Job::new(arguments, false));
         }
 
-        pipelines.push(Pipeline::new(jobs, in_file, out_file));
+        pipelines.push((Pipeline::new(jobs, redirections), connector));
+    }
+}
+
+/// The leading keywords that open or close a control-flow block.
+enum Keyword { If, While, For, Else, End }
+
+/// Finds the end of the first whitespace-delimited token in `line`, honoring quoting and
+/// backslash-escaping so that e.g. `"if" foo` is not mistaken for the `if` keyword.
+fn first_token_end(line: &str) -> usize {
+    let mut flags = 0u8;
+    for (index, byte) in line.bytes().enumerate() {
+        match byte {
+            _ if flags & BACKSLASH != 0                => flags ^= BACKSLASH,
+            b'\\'                                       => flags ^= BACKSLASH,
+            b'\'' if flags & DOUBLE_QUOTE == 0          => flags ^= SINGLE_QUOTE,
+            b'"'  if flags & SINGLE_QUOTE == 0          => flags ^= DOUBLE_QUOTE,
+            b' ' | b'\t' if flags & (BACKSLASH + SINGLE_QUOTE + DOUBLE_QUOTE) == 0 => return index,
+            _ => (),
+        }
+    }
+    line.len()
+}
+
+/// Recognizes a leading `if`/`while`/`for`/`else`/`end` keyword on a statement, ignoring leading
+/// whitespace and quoted/escaped text.
+fn leading_keyword(line: &str) -> Option<Keyword> {
+    let trimmed = line.trim_start();
+    match &trimmed[..first_token_end(trimmed)] {
+        "if"    => Some(Keyword::If),
+        "while" => Some(Keyword::While),
+        "for"   => Some(Keyword::For),
+        "else"  => Some(Keyword::Else),
+        "end"   => Some(Keyword::End),
+        _       => None,
+    }
+}
+
+/// Parses the condition following an `if`/`while` keyword into a single `Pipeline`. Only the
+/// first pipeline is kept: anything after a `&&`/`||` in the condition is dropped along with
+/// the connector.
+fn condition_pipeline(text: &str) -> Pipeline {
+    let mut pipelines = Vec::new();
+    let mut possible_error = None;
+    collect(&mut pipelines, &mut possible_error, text.trim());
+    pipelines.into_iter().next().map(|(pipeline, _)| pipeline).unwrap_or_else(|| Pipeline::new(Vec::new(), Vec::new()))
+}
+
+/// Parses a `for NAME in WORDS` header into the loop variable and the words to iterate over.
+fn for_header(text: &str) -> (String, Vec<String>) {
+    let mut words = text.split_whitespace();
+    let variable = words.next().unwrap_or("").to_owned();
+    if words.next() != Some("in") {
+        return (variable, Vec::new());
     }
+    (variable, words.map(|word| word.to_owned()).collect())
+}
+
+/// An in-progress control-flow block, tracked on a stack while its body is being collected.
+enum Frame {
+    If { condition: Pipeline, then: Vec<Statement>, in_else: bool, els: Vec<Statement> },
+    While { condition: Pipeline, body: Vec<Statement> },
+    For { variable: String, values: Vec<String>, body: Vec<Statement> },
+}
+
+impl Frame {
+    fn into_statement(self) -> Statement {
+        match self {
+            Frame::If { condition, then, els, .. } => Statement::If { condition: condition, then: then, r#else: els },
+            Frame::While { condition, body } => Statement::While { condition: condition, body: body },
+            Frame::For { variable, values, body } => Statement::For { variable: variable, values: values, body: body },
+        }
+    }
+}
+
+/// Attaches a finished statement to the innermost open block, or to the top-level list if no
+/// block is currently open.
+fn push_statement(stack: &mut Vec<Frame>, root: &mut Vec<Statement>, statement: Statement) {
+    match stack.last_mut() {
+        Some(&mut Frame::If { ref mut then, ref mut els, in_else, .. }) => {
+            if in_else { els.push(statement) } else { then.push(statement) }
+        },
+        Some(&mut Frame::While { ref mut body, .. }) | Some(&mut Frame::For { ref mut body, .. }) => {
+            body.push(statement)
+        },
+        None => root.push(statement),
+    }
+}
+
+/// Parses a script into a tree of `Statement`s, recognizing `if`/`while`/`for`/`else`/`end` so
+/// that conditionals and loops nest arbitrarily deep. Each statement produced by
+/// [`PipelineIterator`] (so both `;` and newlines separate statements) is otherwise handed to
+/// [`collect`] as a plain pipeline statement. Reports an error through `possible_error` if the
+/// script ends with an open block that never saw a matching `end`.
+pub fn parse_block(script: &str, possible_error: &mut Option<&str>) -> Vec<Statement> {
+    let mut root: Vec<Statement> = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+
+    for line in PipelineIterator::new(script) {
+        if line.trim().is_empty() { continue }
+
+        match leading_keyword(line) {
+            Some(Keyword::If) => {
+                let condition = condition_pipeline(&line.trim_start()[2..]);
+                stack.push(Frame::If { condition: condition, then: Vec::new(), in_else: false, els: Vec::new() });
+            },
+            Some(Keyword::While) => {
+                let condition = condition_pipeline(&line.trim_start()[5..]);
+                stack.push(Frame::While { condition: condition, body: Vec::new() });
+            },
+            Some(Keyword::For) => {
+                let (variable, values) = for_header(&line.trim_start()[3..]);
+                stack.push(Frame::For { variable: variable, values: values, body: Vec::new() });
+            },
+            Some(Keyword::Else) => {
+                if let Some(&mut Frame::If { ref mut in_else, .. }) = stack.last_mut() {
+                    *in_else = true;
+                }
+            },
+            Some(Keyword::End) => {
+                if let Some(frame) = stack.pop() {
+                    let statement = frame.into_statement();
+                    push_statement(&mut stack, &mut root, statement);
+                }
+            },
+            None => {
+                let mut pipelines = Vec::new();
+                let mut line_error = None;
+                collect(&mut pipelines, &mut line_error, line);
+                if possible_error.is_none() { *possible_error = line_error; }
+                push_statement(&mut stack, &mut root, Statement::Pipelines(pipelines));
+            },
+        }
+    }
+
+    if !stack.is_empty() && possible_error.is_none() {
+        *possible_error = Some("expected 'end' to close block");
+    }
+
+    root
 }
 
 #[cfg(test)]
 mod tests {
     use flow_control::Statement;
-    use parser::peg::parse;
+    use parser::peg::{parse, Connector, RedirectTarget};
 
     #[test]
     fn quoted_process() {
         if let Statement::Pipelines(mut pipelines) = parse("let A = \"$(seq 1 10)\"") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!("let", jobs[0].args[0]);
             assert_eq!("A", jobs[0].args[1]);
             assert_eq!("=", jobs[0].args[2]);
@@ -311,7 +506,7 @@ mod tests {
     #[test]
     fn process() {
         if let Statement::Pipelines(mut pipelines) = parse("let A = $(seq 1 10)") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!("let", jobs[0].args[0]);
             assert_eq!("A", jobs[0].args[1]);
             assert_eq!("=", jobs[0].args[2]);
@@ -322,10 +517,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn arithmetic_process() {
+        if let Statement::Pipelines(mut pipelines) = parse("echo $(( (1+2) * 3 ))") {
+            let jobs = pipelines.remove(0).0.jobs;
+            assert_eq!("echo", jobs[0].args[0]);
+            assert_eq!("$(( (1+2) * 3 ))", jobs[0].args[1]);
+            assert_eq!(2, jobs[0].args.len());
+        } else {
+            assert!(false);
+        }
+    }
+
     #[test]
     fn single_job_no_args() {
         if let Statement::Pipelines(mut pipelines) = parse("cat") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(1, jobs.len());
             assert_eq!("cat", jobs[0].command);
             assert_eq!(1, jobs[0].args.len());
@@ -337,7 +544,7 @@ mod tests {
     #[test]
     fn single_job_with_single_character_arguments() {
         if let Statement::Pipelines(mut pipelines) = parse("echo a b c") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(1, jobs.len());
             assert_eq!("echo", jobs[0].args[0]);
             assert_eq!("a", jobs[0].args[1]);
@@ -352,7 +559,7 @@ mod tests {
     #[test]
     fn job_with_args() {
         if let Statement::Pipelines(mut pipelines) = parse("ls -al dir") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(1, jobs.len());
             assert_eq!("ls", jobs[0].command);
             assert_eq!("-al", jobs[0].args[1]);
@@ -374,7 +581,7 @@ mod tests {
     #[test]
     fn multiple_white_space_between_words() {
         if let Statement::Pipelines(mut pipelines) = parse("ls \t -al\t\tdir") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(1, jobs.len());
             assert_eq!("ls", jobs[0].command);
             assert_eq!("-al", jobs[0].args[1]);
@@ -388,8 +595,8 @@ mod tests {
     fn trailing_whitespace() {
         if let Statement::Pipelines(pipelines) = parse("ls -al\t ") {
             assert_eq!(1, pipelines.len());
-            assert_eq!("ls", pipelines[0].jobs[0].command);
-            assert_eq!("-al", pipelines[0].jobs[0].args[1]);
+            assert_eq!("ls", pipelines[0].0.jobs[0].command);
+            assert_eq!("-al", pipelines[0].0.jobs[0].args[1]);
         } else {
             assert!(false);
         }
@@ -398,7 +605,7 @@ mod tests {
     #[test]
     fn double_quoting() {
         if let Statement::Pipelines(mut pipelines) = parse("echo \"Hello World\" \"From Rust\"") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(3, jobs[0].args.len());
             assert_eq!("\"Hello World\"", jobs[0].args[1]);
             assert_eq!("\"From Rust\"", jobs[0].args[2]);
@@ -412,7 +619,7 @@ mod tests {
     #[test]
     fn double_quoting_contains_single() {
         if let Statement::Pipelines(mut pipelines) = parse("echo \"Hello 'Rusty' World\"") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(2, jobs[0].args.len());
             assert_eq!("\"Hello \'Rusty\' World\"", jobs[0].args[1]);
         } else {
@@ -423,7 +630,7 @@ mod tests {
     #[test]
     fn multi_quotes() {
         if let Statement::Pipelines(mut pipelines) = parse("echo \"Hello \"Rusty\" World\"") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(2, jobs[0].args.len());
             assert_eq!("\"Hello \"Rusty\" World\"", jobs[0].args[1]);
         } else {
@@ -431,7 +638,7 @@ mod tests {
         }
 
         if let Statement::Pipelines(mut pipelines) = parse("echo \'Hello \'Rusty\' World\'") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(2, jobs[0].args.len());
             assert_eq!("\'Hello \'Rusty\' World\'", jobs[0].args[1]);
         } else {
@@ -451,7 +658,7 @@ mod tests {
     #[test]
     fn not_background_job() {
         if let Statement::Pipelines(mut pipelines) = parse("echo hello world") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(false, jobs[0].background);
         } else {
             assert!(false);
@@ -461,7 +668,7 @@ mod tests {
     #[test]
     fn background_job() {
         if let Statement::Pipelines(mut pipelines) = parse("echo hello world&") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(true, jobs[0].background);
         } else {
             assert!(false);
@@ -471,7 +678,7 @@ mod tests {
     #[test]
     fn background_job_with_space() {
         if let Statement::Pipelines(mut pipelines) = parse("echo hello world &") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(true, jobs[0].background);
         } else {
             assert!(false);
@@ -490,7 +697,7 @@ mod tests {
     #[test]
     fn leading_whitespace() {
         if let Statement::Pipelines(mut pipelines) = parse("    \techo") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!(1, jobs.len());
             assert_eq!("echo", jobs[0].command);
         } else {
@@ -501,7 +708,7 @@ mod tests {
     #[test]
     fn single_quoting() {
         if let Statement::Pipelines(mut pipelines) = parse("echo '#!!;\"\\'") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!("'#!!;\"\\'", jobs[0].args[1]);
         } else {
             assert!(false);
@@ -511,7 +718,7 @@ mod tests {
     #[test]
     fn mixed_quoted_and_unquoted() {
         if let Statement::Pipelines(mut pipelines) = parse("echo 123 456 \"ABC 'DEF' GHI\" 789 one'  'two") {
-            let jobs = pipelines.remove(0).jobs;
+            let jobs = pipelines.remove(0).0.jobs;
             assert_eq!("123", jobs[0].args[1]);
             assert_eq!("456", jobs[0].args[2]);
             assert_eq!("\"ABC 'DEF' GHI\"", jobs[0].args[3]);
@@ -534,14 +741,15 @@ mod tests {
     #[test]
     fn pipelines_with_redirection() {
         if let Statement::Pipelines(pipelines) = parse("cat | echo hello | cat < stuff > other") {
-            assert_eq!(3, pipelines[0].jobs.len());
-            assert_eq!("cat", &pipelines[0].clone().jobs[0].args[0]);
-            assert_eq!("echo", &pipelines[0].clone().jobs[1].args[0]);
-            assert_eq!("hello", &pipelines[0].clone().jobs[1].args[1]);
-            assert_eq!("cat", &pipelines[0].clone().jobs[2].args[0]);
-            assert_eq!("stuff", &pipelines[0].clone().stdin.unwrap().file);
-            assert_eq!("other", &pipelines[0].clone().stdout.unwrap().file);
-            assert!(!pipelines[0].clone().stdout.unwrap().append);
+            assert_eq!(3, pipelines[0].0.jobs.len());
+            assert_eq!("cat", &pipelines[0].0.clone().jobs[0].args[0]);
+            assert_eq!("echo", &pipelines[0].0.clone().jobs[1].args[0]);
+            assert_eq!("hello", &pipelines[0].0.clone().jobs[1].args[1]);
+            assert_eq!("cat", &pipelines[0].0.clone().jobs[2].args[0]);
+            assert_eq!(RedirectTarget::File("stuff".to_owned()), pipelines[0].0.clone().stdin.unwrap().to);
+            let stdout = pipelines[0].0.clone().stdout.unwrap();
+            assert_eq!(RedirectTarget::File("other".to_owned()), stdout.to);
+            assert!(!stdout.append);
         } else {
             assert!(false);
         }
@@ -550,10 +758,11 @@ mod tests {
     #[test]
     fn pipeline_with_redirection_append() {
         if let Statement::Pipelines(pipelines) = parse("cat | echo hello | cat < stuff >> other") {
-        assert_eq!(3, pipelines[0].jobs.len());
-        assert_eq!("stuff", &pipelines[0].clone().stdin.unwrap().file);
-        assert_eq!("other", &pipelines[0].clone().stdout.unwrap().file);
-        assert!(pipelines[0].clone().stdout.unwrap().append);
+            assert_eq!(3, pipelines[0].0.jobs.len());
+            assert_eq!(RedirectTarget::File("stuff".to_owned()), pipelines[0].0.clone().stdin.unwrap().to);
+            let stdout = pipelines[0].0.clone().stdout.unwrap();
+            assert_eq!(RedirectTarget::File("other".to_owned()), stdout.to);
+            assert!(stdout.append);
         } else {
             assert!(false);
         }
@@ -562,11 +771,177 @@ mod tests {
     #[test]
     fn pipelines_with_redirection_reverse_order() {
         if let Statement::Pipelines(pipelines) = parse("cat | echo hello | cat > stuff < other") {
-            assert_eq!(3, pipelines[0].jobs.len());
-            assert_eq!("other", &pipelines[0].clone().stdin.unwrap().file);
-            assert_eq!("stuff", &pipelines[0].clone().stdout.unwrap().file);
+            assert_eq!(3, pipelines[0].0.jobs.len());
+            assert_eq!(RedirectTarget::File("other".to_owned()), pipelines[0].0.clone().stdin.unwrap().to);
+            assert_eq!(RedirectTarget::File("stuff".to_owned()), pipelines[0].0.clone().stdout.unwrap().to);
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn stderr_redirection() {
+        if let Statement::Pipelines(pipelines) = parse("cat 2> errors.log") {
+            let redirection = pipelines[0].0.clone().redirections.into_iter().next().unwrap();
+            assert_eq!(2, redirection.from);
+            assert_eq!(RedirectTarget::File("errors.log".to_owned()), redirection.to);
+            assert!(!redirection.append);
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn stderr_append_redirection() {
+        if let Statement::Pipelines(pipelines) = parse("cat 2>> errors.log") {
+            let redirection = pipelines[0].0.clone().redirections.into_iter().next().unwrap();
+            assert_eq!(2, redirection.from);
+            assert!(redirection.append);
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn combined_stdout_stderr_redirection() {
+        if let Statement::Pipelines(pipelines) = parse("cat &> combined.log") {
+            let redirections = pipelines[0].0.clone().redirections;
+            assert_eq!(2, redirections.len());
+            assert!(redirections.iter().any(|r| r.from == 1));
+            assert!(redirections.iter().any(|r| r.from == 2));
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn fd_duplication_redirection() {
+        if let Statement::Pipelines(pipelines) = parse("cat 2>&1") {
+            let redirection = pipelines[0].0.clone().redirections.into_iter().next().unwrap();
+            assert_eq!(2, redirection.from);
+            assert_eq!(RedirectTarget::Fd(1), redirection.to);
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn and_if_connector() {
+        if let Statement::Pipelines(pipelines) = parse("echo one && echo two") {
+            assert_eq!(2, pipelines.len());
+            assert_eq!(Connector::Always, pipelines[0].1);
+            assert_eq!(Connector::AndIf, pipelines[1].1);
+            assert_eq!("one", pipelines[0].0.jobs[0].args[1]);
+            assert_eq!("two", pipelines[1].0.jobs[0].args[1]);
         } else {
             assert!(false);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn or_if_connector() {
+        if let Statement::Pipelines(pipelines) = parse("echo one || echo two") {
+            assert_eq!(2, pipelines.len());
+            assert_eq!(Connector::Always, pipelines[0].1);
+            assert_eq!(Connector::OrIf, pipelines[1].1);
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn chained_connectors() {
+        if let Statement::Pipelines(pipelines) = parse("a && b || c") {
+            assert_eq!(3, pipelines.len());
+            assert_eq!(Connector::Always, pipelines[0].1);
+            assert_eq!(Connector::AndIf, pipelines[1].1);
+            assert_eq!(Connector::OrIf, pipelines[2].1);
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn if_block() {
+        let mut possible_error = None;
+        let statements = super::parse_block("if test 1 -eq 1\necho yes\nend", &mut possible_error);
+        assert_eq!(None, possible_error);
+        assert_eq!(1, statements.len());
+        if let Statement::If { ref then, ref r#else, .. } = statements[0] {
+            assert_eq!(1, then.len());
+            assert_eq!(0, r#else.len());
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn if_else_block() {
+        let mut possible_error = None;
+        let statements = super::parse_block("if test 1 -eq 2\necho yes\nelse\necho no\nend", &mut possible_error);
+        if let Statement::If { ref then, ref r#else, .. } = statements[0] {
+            assert_eq!(1, then.len());
+            assert_eq!(1, r#else.len());
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn while_block() {
+        let mut possible_error = None;
+        let statements = super::parse_block("while test 1 -eq 1\necho loop\nend", &mut possible_error);
+        if let Statement::While { ref body, .. } = statements[0] {
+            assert_eq!(1, body.len());
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn for_block() {
+        let mut possible_error = None;
+        let statements = super::parse_block("for x in a b c\necho $x\nend", &mut possible_error);
+        if let Statement::For { ref variable, ref values, ref body } = statements[0] {
+            assert_eq!("x", variable);
+            assert_eq!(vec!["a".to_owned(), "b".to_owned(), "c".to_owned()], *values);
+            assert_eq!(1, body.len());
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn semicolon_separated_block() {
+        let mut possible_error = None;
+        let statements = super::parse_block("if test 1 -eq 1; echo yes; end", &mut possible_error);
+        assert_eq!(None, possible_error);
+        assert_eq!(1, statements.len());
+        if let Statement::If { ref then, ref r#else, .. } = statements[0] {
+            assert_eq!(1, then.len());
+            assert_eq!(0, r#else.len());
+        } else {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn unterminated_block_reports_error() {
+        let mut possible_error = None;
+        let statements = super::parse_block("if test 1 -eq 1\necho yes", &mut possible_error);
+        assert_eq!(Some("expected 'end' to close block"), possible_error);
+        assert_eq!(0, statements.len());
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let mut possible_error = None;
+        let statements = super::parse_block("if test 1 -eq 1\nwhile test 1 -eq 1\necho hi\nend\nend", &mut possible_error);
+        if let Statement::If { ref then, .. } = statements[0] {
+            assert_eq!(1, then.len());
+            assert!(match then[0] { Statement::While { .. } => true, _ => false });
+        } else {
+            assert!(false);
+        }
+    }
+}